@@ -41,8 +41,13 @@ impl<const SIZE: usize> GuestMemory<SIZE> {
 
     pub fn read<T>(&self, guest_addr: u64) -> T {
         let mut tmp = MaybeUninit::<T>::uninit();
-        let dst = unsafe { core::slice::from_raw_parts_mut(tmp.as_mut_ptr() as *mut u8, size_of::<T>()) }; 
+        let dst = unsafe { core::slice::from_raw_parts_mut(tmp.as_mut_ptr() as *mut u8, size_of::<T>()) };
         self.read_bytes(guest_addr, dst);
         unsafe { tmp.assume_init() }
     }
+
+    pub fn write<T>(&self, guest_addr: u64, value: &T) {
+        let src = unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+        self.write_bytes(guest_addr, src);
+    }
 }
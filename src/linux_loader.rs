@@ -24,7 +24,30 @@ pub const PLIC_ADDR: u64 = 0x0c00_0000;
 pub const PLIC_END: u64 = PLIC_ADDR + 0x400000;
 pub const VIRTIO_BLK_ADDR: u64 = 0x0a00_0000;
 pub const VIRTIO_BLK_END: u64 = VIRTIO_BLK_ADDR + 0x1000;
-const MEMORY_SIZE: usize = 64 * 1024 * 1024;
+pub const VIRTIO_ENTROPY_ADDR: u64 = 0x0a00_1000;
+pub const VIRTIO_ENTROPY_END: u64 = VIRTIO_ENTROPY_ADDR + 0x1000;
+pub const VIRTIO_CONSOLE_ADDR: u64 = 0x0a00_2000;
+pub const VIRTIO_CONSOLE_END: u64 = VIRTIO_CONSOLE_ADDR + 0x1000;
+/// Capacity of the `GuestMemory` backing array (a const generic, fixed at compile
+/// time), and therefore the upper bound any `GuestConfig::memory_bytes` must fit in.
+pub const MEMORY_SIZE: usize = 64 * 1024 * 1024;
+
+/// Sizing knobs for a guest: how much RAM to map/advertise and how many harts to
+/// describe in the device tree. Note that only hart 0 is actually booted today --
+/// this hypervisor has no SBI HSM extension or secondary-hart boot path -- so
+/// `num_harts` beyond 1 only affects what topology the guest sees in its device tree.
+pub struct GuestConfig {
+    pub memory_bytes: u64,
+    pub num_harts: u32,
+}
+
+impl GuestConfig {
+    pub fn new(memory_bytes: u64, num_harts: u32) -> Self {
+        assert!(num_harts >= 1, "a guest needs at least one hart");
+        assert!(memory_bytes <= MEMORY_SIZE as u64, "memory_bytes exceeds the GuestMemory capacity");
+        Self { memory_bytes, num_harts }
+    }
+}
 
 fn copy_and_map(table: &mut GuestPageTable, data: &[u8], guest_addr: u64, len: usize, flags: u64) {
     assert!(data.len() <= len, "data is beyond the region");
@@ -37,23 +60,23 @@ fn copy_and_map(table: &mut GuestPageTable, data: &[u8], guest_addr: u64, len: u
     }
 }
 
-pub fn load_linux_kernel(table: &mut GuestPageTable, image: &[u8]) {
+pub fn load_linux_kernel(table: &mut GuestPageTable, image: &[u8], config: &GuestConfig) {
     assert!(image.len() >= size_of::<RiscvImageHeader>());
     let header = unsafe { &*(image.as_ptr() as *const RiscvImageHeader) };
     assert_eq!(u32::from_le(header.magic2), 0x05435352, "invalid magic");
 
     let kernel_size = u64::from_le(header.image_size);
-    assert!(image.len() <= MEMORY_SIZE);
-    copy_and_map(table, image, GUEST_BASE_ADDR, MEMORY_SIZE, PTE_R | PTE_W | PTE_X);
+    assert!(image.len() as u64 <= config.memory_bytes, "kernel image is bigger than the guest's memory");
+    copy_and_map(table, image, GUEST_BASE_ADDR, config.memory_bytes as usize, PTE_R | PTE_W | PTE_X);
 
-    let dtb = build_device_tree().unwrap();
+    let dtb = build_device_tree(config).unwrap();
     assert!(dtb.len() <= 0x10000, "DTB is too large");
     copy_and_map(table, &dtb, GUEST_DTB_ADDR, dtb.len(), PTE_R);
 
     println!("loaded kernel: size={}KB", kernel_size / 1024);
 }
 
-fn build_device_tree() -> Result<Vec<u8>, vm_fdt::Error> {
+fn build_device_tree(config: &GuestConfig) -> Result<Vec<u8>, vm_fdt::Error> {
     let mut fdt = vm_fdt::FdtWriter::new()?;
     let root_node = fdt.begin_node("")?;
     fdt.property_string("compatible", "riscv-virtio")?;
@@ -62,11 +85,12 @@ fn build_device_tree() -> Result<Vec<u8>, vm_fdt::Error> {
 
     let chosen_node = fdt.begin_node("chosen")?;
     fdt.property_string("bootargs", "console=hvc earlycon=sbi panic=-1 root=/dev/vda init=/bin/catsay")?;
+    fdt.property_string("stdout-path", "/virtio_mmio@a002000")?;
     fdt.end_node(chosen_node)?;
 
     let memory_node = fdt.begin_node(&format!("memory@{}", GUEST_BASE_ADDR))?;
     fdt.property_string("device_type", "memory")?;
-    fdt.property_array_u64("reg", &[GUEST_BASE_ADDR, MEMORY_SIZE as u64])?;
+    fdt.property_array_u64("reg", &[GUEST_BASE_ADDR, config.memory_bytes])?;
     fdt.end_node(memory_node)?;
 
     let cpus_node = fdt.begin_node("cpus")?;
@@ -74,40 +98,68 @@ fn build_device_tree() -> Result<Vec<u8>, vm_fdt::Error> {
     fdt.property_u32("#size-cells", 0x0)?;
     fdt.property_u32("timebase-frequency", 10000000)?;
 
-    let cpu_node = fdt.begin_node("cpu@0")?;
-    fdt.property_string("device_type", "cpu")?;
-    fdt.property_string("compatible", "riscv")?;
-    fdt.property_u32("reg", 0)?;
-    fdt.property_string("status", "okay")?;
-    fdt.property_string("mmu-type", "riscv,sv48")?;
-    fdt.property_string("riscv,isa", "rv64imafdc")?;
-
-    let intc_node = fdt.begin_node("interrupt-controller")?;
-    fdt.property_u32("#interrupt-cells", 1)?;
-    fdt.property_null("interrupt-controller")?;
-    fdt.property_string("compatible", "riscv,cpu-intc")?;
-    fdt.property_phandle(1)?;
-    fdt.end_node(intc_node)?;
-
-    fdt.end_node(cpu_node)?;
+    // Each hart gets its own `interrupt-controller` node and phandle, so the PLIC's
+    // `interrupts-extended` can address every hart's S-mode/M-mode contexts below.
+    // Phandle 0 is reserved (FDT phandles start at 1), so hart `k` is phandle `k + 1`
+    // and the PLIC itself takes the next one after the last hart.
+    for hart in 0..config.num_harts {
+        let intc_phandle = hart + 1;
+        let cpu_node = fdt.begin_node(&format!("cpu@{}", hart))?;
+        fdt.property_string("device_type", "cpu")?;
+        fdt.property_string("compatible", "riscv")?;
+        fdt.property_u32("reg", hart)?;
+        fdt.property_string("status", "okay")?;
+        fdt.property_string("mmu-type", "riscv,sv48")?;
+        fdt.property_string("riscv,isa", "rv64imafdc")?;
+
+        let intc_node = fdt.begin_node("interrupt-controller")?;
+        fdt.property_u32("#interrupt-cells", 1)?;
+        fdt.property_null("interrupt-controller")?;
+        fdt.property_string("compatible", "riscv,cpu-intc")?;
+        fdt.property_phandle(intc_phandle)?;
+        fdt.end_node(intc_node)?;
+
+        fdt.end_node(cpu_node)?;
+    }
     fdt.end_node(cpus_node)?;
 
+    let plic_phandle = config.num_harts + 1;
+    let mut plic_interrupts_extended = Vec::with_capacity(config.num_harts as usize * 4);
+    for hart in 0..config.num_harts {
+        let intc_phandle = hart + 1;
+        plic_interrupts_extended.extend_from_slice(&[intc_phandle, 11, intc_phandle, 9]);
+    }
+
     let plic_node = fdt.begin_node("plic@c000000")?;
     fdt.property_string("compatible", "riscv,plic0")?;
     fdt.property_u32("#interrupt-cells", 1)?;
     fdt.property_null("interrupt-controller")?;
     fdt.property_array_u64("reg", &[PLIC_ADDR, 0x4000000])?;
     fdt.property_u32("riscv,ndev", 3)?;
-    fdt.property_array_u32("interrupts-extended", &[1, 11, 1, 9])?;
-    fdt.property_phandle(2)?;
+    fdt.property_array_u32("interrupts-extended", &plic_interrupts_extended)?;
+    fdt.property_phandle(plic_phandle)?;
     fdt.end_node(plic_node)?;
 
-    let virtio_node = fdt.begin_node("virtio_mmio@a000000")?;
+    let virtio_blk_node = fdt.begin_node("virtio_mmio@a000000")?;
     fdt.property_string("compatible", "virtio,mmio")?;
     fdt.property_array_u64("reg", &[VIRTIO_BLK_ADDR, 0x1000])?;
-    fdt.property_u32("interrupt-parent", 2)?;
+    fdt.property_u32("interrupt-parent", plic_phandle)?;
     fdt.property_array_u32("interrupts", &[1])?;
-    fdt.end_node(virtio_node)?;
+    fdt.end_node(virtio_blk_node)?;
+
+    let virtio_entropy_node = fdt.begin_node("virtio_mmio@a001000")?;
+    fdt.property_string("compatible", "virtio,mmio")?;
+    fdt.property_array_u64("reg", &[VIRTIO_ENTROPY_ADDR, 0x1000])?;
+    fdt.property_u32("interrupt-parent", plic_phandle)?;
+    fdt.property_array_u32("interrupts", &[2])?;
+    fdt.end_node(virtio_entropy_node)?;
+
+    let virtio_console_node = fdt.begin_node("virtio_mmio@a002000")?;
+    fdt.property_string("compatible", "virtio,mmio")?;
+    fdt.property_array_u64("reg", &[VIRTIO_CONSOLE_ADDR, 0x1000])?;
+    fdt.property_u32("interrupt-parent", plic_phandle)?;
+    fdt.property_array_u32("interrupts", &[3])?;
+    fdt.end_node(virtio_console_node)?;
 
     fdt.end_node(root_node)?;
     fdt.finish()
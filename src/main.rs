@@ -11,14 +11,20 @@ mod plic;
 mod trap;
 mod vcpu;
 mod linux_loader;
+mod virtio;
 mod virtio_blk;
+mod virtio_console;
+mod virtio_entropy;
+mod virtqueue;
 mod guest_memory;
 
 use core::arch::asm;
 use core::panic::PanicInfo;
 
 use crate::{
-    guest_page_table::GuestPageTable, linux_loader::{GUEST_BASE_ADDR, GUEST_DTB_ADDR}, vcpu::VCpu
+    guest_page_table::GuestPageTable,
+    linux_loader::{GUEST_BASE_ADDR, GUEST_DTB_ADDR, GuestConfig, MEMORY_SIZE},
+    vcpu::VCpu,
 };
 
 #[unsafe(no_mangle)]
@@ -56,7 +62,10 @@ fn main() -> ! {
 
     let kernel_image = include_bytes!("../linux/Image");
     let mut table = GuestPageTable::new();
-    linux_loader::load_linux_kernel(&mut table, kernel_image);
+    // Only hart 0 is booted below -- there's no SBI HSM extension or secondary-hart
+    // boot path yet -- so `num_harts` is 1 until that lands.
+    let config = GuestConfig::new(MEMORY_SIZE as u64, 1);
+    linux_loader::load_linux_kernel(&mut table, kernel_image, &config);
     let mut vcpu = VCpu::new(&table, GUEST_BASE_ADDR);
     vcpu.a0 = 0; // hart ID
     vcpu.a1 = GUEST_DTB_ADDR; // device tree address
@@ -1,9 +1,28 @@
+use core::arch::asm;
+
 use alloc::collections::BTreeSet;
 use spin::Mutex;
 
 
 pub static PLIC: Mutex<Plic> = Mutex::new(Plic::new());
 
+/// VSEIP: bit 10 of `hvip`, the VS-mode external-interrupt-pending bit.
+const HVIP_VSEIP: u64 = 1 << 10;
+
+fn read_hvip() -> u64 {
+    let mut value: u64;
+    unsafe {
+        asm!("csrr {}, hvip", out(reg) value);
+    }
+    value
+}
+
+fn write_hvip(value: u64) {
+    unsafe {
+        asm!("csrw hvip, {}", in(reg) value);
+    }
+}
+
 #[derive(Debug)]
 pub struct Plic {
     irq_pending: BTreeSet<u16>,
@@ -21,9 +40,8 @@ impl Plic {
             (0x200000..0x4000000, 4) if (offset & 0xfff) == 0 => {},  // Priority threshold
             (0x200000..0x4000000, 4) if (offset & 0xfff) == 4 => {
                 // IRQ claim/completion
-                if value == 1 {
-                    self.irq_pending.remove(&1);
-                }
+                self.irq_pending.remove(&(value as u16));
+                self.sync_hvip();
             }
             _ => println!("[PLIC]: unknown write offset={:#x} (value={:#x}, width={})", offset, value, width),
         }
@@ -45,9 +63,20 @@ impl Plic {
 
     pub fn add_pending_irq(&mut self, irq: u16) {
         self.irq_pending.insert(irq);
+        self.sync_hvip();
     }
 
     pub fn has_pending_irqs(&self) -> bool {
         !self.irq_pending.is_empty()
     }
+
+    /// Reflects `has_pending_irqs()` into `hvip`'s VSEIP bit, so a completion actually
+    /// traps the guest instead of relying on it to poll the used ring/InterruptStatus.
+    fn sync_hvip(&self) {
+        if self.has_pending_irqs() {
+            write_hvip(read_hvip() | HVIP_VSEIP);
+        } else {
+            write_hvip(read_hvip() & !HVIP_VSEIP);
+        }
+    }
 }
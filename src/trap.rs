@@ -2,7 +2,18 @@ use core::{arch::naked_asm, mem::offset_of};
 use alloc::vec::Vec;
 use spin::Mutex;
 
-use crate::{vcpu::VCpu, linux_loader::{GUEST_PLIC_ADDR, GUEST_VIRTIO_BLK_ADDR}, plic::PLIC};
+use crate::{
+    vcpu::VCpu,
+    linux_loader::{
+        PLIC_ADDR, PLIC_END, VIRTIO_BLK_ADDR, VIRTIO_BLK_END, VIRTIO_CONSOLE_ADDR, VIRTIO_CONSOLE_END,
+        VIRTIO_ENTROPY_ADDR, VIRTIO_ENTROPY_END,
+    },
+    plic::PLIC,
+    virtio::VirtioDevice,
+    virtio_blk::VIRTIO_BLK,
+    virtio_console::VIRTIO_CONSOLE,
+    virtio_entropy::VIRTIO_ENTROPY,
+};
 
 macro_rules! read_csr {
     ($csr:expr) => {{
@@ -14,6 +25,19 @@ macro_rules! read_csr {
     }};
 }
 
+macro_rules! write_csr {
+    ($csr:expr, $value:expr) => {{
+        unsafe {
+            ::core::arch::asm!(concat!("csrw ", $csr, ", {}"), in(reg) $value);
+        }
+    }};
+}
+
+/// VSTIP: bit 6 of `hvip`, the VS-mode timer interrupt pending bit.
+const HVIP_VSTIP: u64 = 1 << 6;
+/// STIE: bit 5 of `sie`, the host supervisor-timer interrupt enable bit.
+const SIE_STIE: u64 = 1 << 5;
+
 #[unsafe(link_section = ".text.stvec")]
 #[unsafe(naked)]
 pub extern "C" fn trap_handler() -> ! {
@@ -100,13 +124,30 @@ pub extern "C" fn trap_handler() -> ! {
 
 static CONSOLE_BUFFER: Mutex<Vec<u8>> = Mutex::new(Vec::new());
 
+/// Arms the guest's virtual timer for absolute guest time `deadline` (as read from
+/// the `time` CSR), per the legacy `SBI_SET_TIMER` / TIME extension `set_timer` call.
+fn set_timer(vcpu: &mut VCpu, deadline: u64) {
+    vcpu.timer_deadline = deadline;
+
+    // The timer hasn't fired yet: drop any stale pending interrupt and program the
+    // host timer (Sstc `stimecmp`) to trap us back in once the deadline is reached.
+    write_csr!("hvip", read_csr!("hvip") & !HVIP_VSTIP);
+    write_csr!("stimecmp", deadline);
+    write_csr!("sie", read_csr!("sie") | SIE_STIE);
+}
+
 fn handle_sbi_call(vcpu: &mut VCpu) {
     let eid = vcpu.a7;
     let fid = vcpu.a6;
     let result: Result<i64, i64> = match (eid, fid) {
-        // Set Timer
-        (0x00, 0x0) => {
-            println!("[sbi] WARN: set_timer is not implemented, ignoring");
+        // Set Timer (legacy extension)
+        (0x00, _) => {
+            set_timer(vcpu, vcpu.a0);
+            Ok(0)
+        }
+        // TIME extension: set_timer
+        (0x5449_4d45, 0x0) => {
+            set_timer(vcpu, vcpu.a0);
             Ok(0)
         }
         // Get SBI specification version
@@ -184,24 +225,36 @@ fn handle_mmio_write(vcpu: &mut VCpu, guest_addr: u64, reg: u64, width: u64) {
         _ => unreachable!(),
     };
 
-    if GUEST_PLIC_ADDR <= guest_addr && guest_addr < GUEST_PLIC_ADDR + 0x400000 {
-        let offset = guest_addr - GUEST_PLIC_ADDR;
+    if PLIC_ADDR <= guest_addr && guest_addr < PLIC_END {
+        let offset = guest_addr - PLIC_ADDR;
         PLIC.lock().handle_write(offset, value, width);
-    } else if GUEST_VIRTIO_BLK_ADDR <= guest_addr && guest_addr < GUEST_VIRTIO_BLK_ADDR + 0x1000 {
-        let offset = guest_addr - GUEST_VIRTIO_BLK_ADDR;
-        vcpu.virtio_blk.handle_mmio_write(offset, value, width);
+    } else if VIRTIO_BLK_ADDR <= guest_addr && guest_addr < VIRTIO_BLK_END {
+        let offset = guest_addr - VIRTIO_BLK_ADDR;
+        VIRTIO_BLK.lock().handle_mmio_write(offset, value, width);
+    } else if VIRTIO_ENTROPY_ADDR <= guest_addr && guest_addr < VIRTIO_ENTROPY_END {
+        let offset = guest_addr - VIRTIO_ENTROPY_ADDR;
+        VIRTIO_ENTROPY.lock().handle_mmio_write(offset, value, width);
+    } else if VIRTIO_CONSOLE_ADDR <= guest_addr && guest_addr < VIRTIO_CONSOLE_END {
+        let offset = guest_addr - VIRTIO_CONSOLE_ADDR;
+        VIRTIO_CONSOLE.lock().handle_mmio_write(offset, value, width);
     } else {
         println!("[MMIO]: write {:#x} (value={:#x}, width={})", guest_addr, value, width);
     }
 }
 
 fn handle_mmio_read(vcpu: &mut VCpu, guest_addr: u64, reg: u64, width: u64) {
-    let value = if GUEST_PLIC_ADDR <= guest_addr && guest_addr < GUEST_PLIC_ADDR + 0x400000 {
-        let offset = guest_addr - GUEST_PLIC_ADDR;
+    let value = if PLIC_ADDR <= guest_addr && guest_addr < PLIC_END {
+        let offset = guest_addr - PLIC_ADDR;
         PLIC.lock().handle_read(offset, width)
-    } else if GUEST_VIRTIO_BLK_ADDR <= guest_addr && guest_addr < GUEST_VIRTIO_BLK_ADDR + 0x1000 {
-        let offset = guest_addr - GUEST_VIRTIO_BLK_ADDR;
-        vcpu.virtio_blk.handle_mmio_read(offset, width)
+    } else if VIRTIO_BLK_ADDR <= guest_addr && guest_addr < VIRTIO_BLK_END {
+        let offset = guest_addr - VIRTIO_BLK_ADDR;
+        VIRTIO_BLK.lock().handle_mmio_read(offset, width)
+    } else if VIRTIO_ENTROPY_ADDR <= guest_addr && guest_addr < VIRTIO_ENTROPY_END {
+        let offset = guest_addr - VIRTIO_ENTROPY_ADDR;
+        VIRTIO_ENTROPY.lock().handle_mmio_read(offset, width)
+    } else if VIRTIO_CONSOLE_ADDR <= guest_addr && guest_addr < VIRTIO_CONSOLE_END {
+        let offset = guest_addr - VIRTIO_CONSOLE_ADDR;
+        VIRTIO_CONSOLE.lock().handle_mmio_read(offset, width)
     } else {
         println!("[MMIO]: read {:#x} (width={})", guest_addr, width);
         0
@@ -289,6 +342,12 @@ pub fn handle_trap(vcpu: *mut VCpu) -> ! {
             handle_sbi_call(vcpu);
             vcpu.sepc = sepc + 4;
         }
+        0x8000_0000_0000_0005 /* supervisor timer interrupt */ => {
+            // The guest's deadline has passed: deliver a VS-mode timer interrupt and
+            // mask the host timer until the guest re-arms it with another set_timer.
+            write_csr!("hvip", read_csr!("hvip") | HVIP_VSTIP);
+            write_csr!("sie", read_csr!("sie") & !SIE_STIE);
+        }
         21 /* load guest-page fault */ | 23 /* store/AMO guest-page fault */ => {
             let htinst = read_csr!("htinst");
             let htval = read_csr!("htval");
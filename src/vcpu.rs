@@ -9,6 +9,9 @@ pub struct VCpu {
     pub hgatp: u64,
     pub sstatus: u64,
     pub sepc: u64,
+    /// Absolute guest time (in `time` CSR ticks) requested by the last `sbi_set_timer`
+    /// call, i.e. when the next VS-mode timer interrupt should fire.
+    pub timer_deadline: u64,
     pub ra: u64,
     pub sp: u64,
     pub gp: u64,
@@ -52,6 +55,13 @@ impl VCpu {
 
         let stack_size = 512 * 1024;
         let host_sp = alloc_pages(stack_size) as u64 + stack_size as u64;
+
+        unsafe {
+            // Guest `time` reads should match the host's, so the guest doesn't need to
+            // account for an offset when arming its timer.
+            asm!("csrw htimedelta, 0");
+        }
+
         Self {
             hstatus,
             hgatp: table.hgatp(),
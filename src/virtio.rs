@@ -0,0 +1,194 @@
+//! Common virtio-mmio register handling shared by every device on the bus.
+
+use core::mem::offset_of;
+use core::num::Wrapping;
+use core::sync::atomic::{fence, Ordering};
+
+use crate::guest_memory::GUEST_MEMORY;
+use crate::plic::PLIC;
+use crate::virtqueue::{VirtqAvail, VirtqUsed, VirtqUsedElem, MAX_QUEUE_SIZE};
+
+const VIRTIO_MMIO_MAGIC: u64 = 0x7472_6976; // "virt"
+const VIRTIO_MMIO_VERSION: u64 = 0x2;
+const VIRTIO_MMIO_VENDOR_ID: u64 = 0x554d_4551; // "QEMU"
+const VIRTIO_F_VERSION_1: u64 = 0x0000_0001;
+
+const MAX_QUEUES: usize = 2; // virtio-console needs 2: receiveq and transmitq
+
+// virtio-mmio InterruptStatus bit for "used ring updated".
+const VIRTIO_MMIO_INT_VRING: u32 = 1 << 0;
+
+#[derive(Clone, Copy)]
+struct QueueState {
+    ready: u32,
+    size: u32,
+    desc_addr: u64,
+    driver_addr: u64,
+    device_addr: u64,
+    next_avail: Wrapping<u16>,
+    used_idx: Wrapping<u16>,
+}
+
+impl QueueState {
+    const fn new() -> Self {
+        Self {
+            ready: 0,
+            size: 0,
+            desc_addr: 0,
+            driver_addr: 0,
+            device_addr: 0,
+            next_avail: Wrapping(0),
+            used_idx: Wrapping(0),
+        }
+    }
+
+    /// Returns the head descriptor index of the next unprocessed request, if any.
+    fn pop_avail(&mut self) -> Option<u16> {
+        let avail: VirtqAvail = GUEST_MEMORY.read(self.driver_addr);
+        if self.next_avail.0 == u16::from_le(avail.idx) {
+            return None;
+        }
+        let avail_index = self.next_avail.0 as u64 % self.size as u64;
+        let head = u16::from_le(avail.ring[avail_index as usize]);
+        self.next_avail += 1;
+        Some(head)
+    }
+
+    /// Publishes a completion for descriptor chain `head` that wrote `len` bytes.
+    fn complete(&mut self, head: u16, len: u32) {
+        let used_index = self.used_idx.0 as u64 % self.size as u64;
+        let elem = VirtqUsedElem { id: (head as u32).to_le(), len: len.to_le() };
+        GUEST_MEMORY.write(
+            self.device_addr + offset_of!(VirtqUsed, ring) as u64 + used_index * size_of::<VirtqUsedElem>() as u64,
+            &elem,
+        );
+
+        // Publish the element before bumping `idx` so the guest never observes an
+        // advanced index with stale ring contents.
+        fence(Ordering::Release);
+        self.used_idx += 1;
+        GUEST_MEMORY.write(self.device_addr + offset_of!(VirtqUsed, idx) as u64, &self.used_idx.0.to_le());
+    }
+}
+
+/// Registers common to every virtio-mmio device.
+pub struct VirtioMmioCommon {
+    status: u32,
+    device_features_sel: u32,
+    driver_features_sel: u32,
+    queue_sel: u32,
+    queues: [QueueState; MAX_QUEUES],
+    interrupt_status: u32,
+}
+
+impl VirtioMmioCommon {
+    pub const fn new() -> Self {
+        Self {
+            status: 0,
+            device_features_sel: 0,
+            driver_features_sel: 0,
+            queue_sel: 0,
+            queues: [QueueState::new(); MAX_QUEUES],
+            interrupt_status: 0,
+        }
+    }
+
+    pub fn pop_avail(&mut self, queue_index: usize) -> Option<u16> {
+        self.queues[queue_index].pop_avail()
+    }
+
+    pub fn desc_table_addr(&self, queue_index: usize) -> u64 {
+        self.queues[queue_index].desc_addr
+    }
+
+    pub fn queue_size(&self, queue_index: usize) -> u32 {
+        self.queues[queue_index].size
+    }
+}
+
+fn set_low_32(value: &mut u64, low: u64) {
+    *value = (*value & 0xffff_ffff_0000_0000) | low;
+}
+
+fn set_high_32(value: &mut u64, high: u64) {
+    *value = (*value & 0x0000_0000_ffff_ffff) | (high << 32);
+}
+
+/// A virtio-mmio device. Implementors provide the device-id, device-specific config
+/// space, and per-queue request processing; `handle_mmio_{read,write}` wire that up
+/// to the MMIO register layout.
+pub trait VirtioDevice {
+    /// Reported at MMIO offset 0x08 (e.g. 2 for block, 4 for entropy).
+    const DEVICE_ID: u32;
+
+    /// IRQ this device claims on the PLIC -- must match its `interrupts` property on
+    /// the `virtio_mmio@...` node `build_device_tree` emits for it.
+    fn irq(&self) -> u16;
+
+    fn common(&self) -> &VirtioMmioCommon;
+    fn common_mut(&mut self) -> &mut VirtioMmioCommon;
+
+    /// Reads device-specific config space (offset relative to 0x100).
+    fn config_read(&self, offset: u64, width: u64) -> u64;
+
+    /// Services all newly-available requests on `queue_index`.
+    fn process_queue(&mut self, queue_index: usize);
+
+    /// Completes descriptor chain `head` on `queue_index` having written `len` bytes,
+    /// and raises the device's interrupt.
+    fn complete(&mut self, queue_index: usize, head: u16, len: u32) {
+        let irq = self.irq();
+        let common = self.common_mut();
+        common.queues[queue_index].complete(head, len);
+        common.interrupt_status |= VIRTIO_MMIO_INT_VRING;
+        PLIC.lock().add_pending_irq(irq);
+    }
+
+    fn handle_mmio_write(&mut self, offset: u64, value: u64, width: u64) {
+        assert_eq!(width, 4);
+        match offset {
+            0x14 => self.common_mut().device_features_sel = value as u32,
+            0x20 => {} // Driver features (ignored: we don't offer any optional features)
+            0x24 => self.common_mut().driver_features_sel = value as u32,
+            0x30 => self.common_mut().queue_sel = value as u32,
+            0x38 => { let sel = self.common().queue_sel as usize; self.common_mut().queues[sel].size = value as u32; }
+            0x44 => { let sel = self.common().queue_sel as usize; self.common_mut().queues[sel].ready = value as u32; }
+            0x50 => self.process_queue(value as usize), // Queue notify
+            0x64 => self.common_mut().interrupt_status &= !(value as u32), // InterruptACK
+            0x70 => self.common_mut().status = value as u32,
+            0x80 => { let sel = self.common().queue_sel as usize; set_low_32(&mut self.common_mut().queues[sel].desc_addr, value); }
+            0x84 => { let sel = self.common().queue_sel as usize; set_high_32(&mut self.common_mut().queues[sel].desc_addr, value); }
+            0x90 => { let sel = self.common().queue_sel as usize; set_low_32(&mut self.common_mut().queues[sel].driver_addr, value); }
+            0x94 => { let sel = self.common().queue_sel as usize; set_high_32(&mut self.common_mut().queues[sel].driver_addr, value); }
+            0xa0 => { let sel = self.common().queue_sel as usize; set_low_32(&mut self.common_mut().queues[sel].device_addr, value); }
+            0xa4 => { let sel = self.common().queue_sel as usize; set_high_32(&mut self.common_mut().queues[sel].device_addr, value); }
+            _ => panic!("unknown virtio-mmio write: offs={:#x}", offset),
+        }
+    }
+
+    fn handle_mmio_read(&self, offset: u64, width: u64) -> u64 {
+        assert_eq!(width, 4);
+        let sel = self.common().queue_sel as usize;
+        match offset {
+            0x00 => VIRTIO_MMIO_MAGIC,
+            0x04 => VIRTIO_MMIO_VERSION,
+            0x08 => Self::DEVICE_ID as u64,
+            0x0c => VIRTIO_MMIO_VENDOR_ID,
+            0x10 if self.common().device_features_sel == 0 => 0,
+            0x10 if self.common().device_features_sel == 1 => VIRTIO_F_VERSION_1,
+            0x34 => MAX_QUEUE_SIZE as u64,
+            0x44 => self.common().queues[sel].ready as u64,
+            0x60 => self.common().interrupt_status as u64,
+            0x70 => self.common().status as u64,
+            0x80 => self.common().queues[sel].desc_addr & 0xffff_ffff,
+            0x84 => self.common().queues[sel].desc_addr >> 32,
+            0x90 => self.common().queues[sel].driver_addr & 0xffff_ffff,
+            0x94 => self.common().queues[sel].driver_addr >> 32,
+            0xa0 => self.common().queues[sel].device_addr & 0xffff_ffff,
+            0xa4 => self.common().queues[sel].device_addr >> 32,
+            0xfc => 0x0, // Config generation
+            _ if offset >= 0x100 => self.config_read(offset - 0x100, width),
+            _ => panic!("unknown virtio-mmio read: offs={:#x}", offset),
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use alloc::collections::VecDeque;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::virtio::{VirtioDevice, VirtioMmioCommon};
+use crate::virtqueue::{ChainReader, ChainWriter, DescriptorChain, VirtqDesc, VIRTQ_DESC_F_WRITE};
+
+const VIRTIO_CONSOLE_IRQ: u16 = 3; // see VirtioDevice::irq
+const RECEIVEQ: usize = 0;
+const TRANSMITQ: usize = 1;
+
+pub static VIRTIO_CONSOLE: Mutex<VirtioConsole> = Mutex::new(VirtioConsole::new());
+
+/// Bytes received from the host side of the console, staged into the guest's
+/// receiveq buffers by `process_receiveq`.
+static HOST_INPUT: Mutex<VecDeque<u8>> = Mutex::new(VecDeque::new());
+
+// Unused scaffolding: no host input path feeds this yet.
+#[allow(dead_code)]
+pub fn push_host_input(bytes: &[u8]) {
+    HOST_INPUT.lock().extend(bytes.iter().copied());
+}
+
+pub struct VirtioConsole {
+    common: VirtioMmioCommon,
+    out_buffer: Vec<u8>,
+}
+
+impl VirtioConsole {
+    pub const fn new() -> Self {
+        Self { common: VirtioMmioCommon::new(), out_buffer: Vec::new() }
+    }
+
+    fn write_output(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if byte == b'\n' {
+                let line = core::str::from_utf8(&self.out_buffer).unwrap_or("(not utf-8)");
+                println!("[hvc] {}", line);
+                self.out_buffer.clear();
+            } else {
+                self.out_buffer.push(byte);
+            }
+        }
+    }
+
+    fn process_transmitq(&mut self) {
+        while let Some(head) = self.common.pop_avail(TRANSMITQ) {
+            let desc_table_addr = self.common.desc_table_addr(TRANSMITQ);
+            let queue_size = self.common.queue_size(TRANSMITQ);
+            let descs: Vec<(u16, VirtqDesc)> = DescriptorChain::new(desc_table_addr, queue_size, head).collect();
+            for (_, desc) in &descs {
+                assert!(desc.flags & VIRTQ_DESC_F_WRITE == 0, "virtio-console transmitq buffers must be device-readable");
+            }
+            let total_len: u32 = descs.iter().map(|(_, d)| d.len).sum();
+
+            let mut buf = vec![0u8; total_len as usize];
+            ChainReader::new(descs.iter().map(|(_, d)| *d)).read_bytes(&mut buf);
+            self.write_output(&buf);
+
+            self.complete(TRANSMITQ, head, 0);
+        }
+    }
+
+    fn process_receiveq(&mut self) {
+        // Only pop a posted buffer once there's something to put in it, so an empty
+        // `HOST_INPUT` leaves the guest's buffer queued for later.
+        while !HOST_INPUT.lock().is_empty() {
+            let Some(head) = self.common.pop_avail(RECEIVEQ) else { break };
+            let desc_table_addr = self.common.desc_table_addr(RECEIVEQ);
+            let queue_size = self.common.queue_size(RECEIVEQ);
+            let descs: Vec<(u16, VirtqDesc)> = DescriptorChain::new(desc_table_addr, queue_size, head).collect();
+            for (_, desc) in &descs {
+                assert!(desc.flags & VIRTQ_DESC_F_WRITE != 0, "virtio-console receiveq buffers must be device-writable");
+            }
+            let capacity: u32 = descs.iter().map(|(_, d)| d.len).sum();
+
+            let buf: Vec<u8> = {
+                let mut input = HOST_INPUT.lock();
+                let n = core::cmp::min(capacity as usize, input.len());
+                (0..n).map(|_| input.pop_front().unwrap()).collect()
+            };
+
+            let mut writer = ChainWriter::new(descs.iter().map(|(_, d)| *d));
+            writer.write_bytes(&buf);
+            self.complete(RECEIVEQ, head, writer.bytes_written);
+        }
+    }
+}
+
+impl VirtioDevice for VirtioConsole {
+    const DEVICE_ID: u32 = 3;
+
+    fn irq(&self) -> u16 {
+        VIRTIO_CONSOLE_IRQ
+    }
+
+    fn common(&self) -> &VirtioMmioCommon {
+        &self.common
+    }
+
+    fn common_mut(&mut self) -> &mut VirtioMmioCommon {
+        &mut self.common
+    }
+
+    fn config_read(&self, offset: u64, _width: u64) -> u64 {
+        panic!("virtio-console has no device-specific config: offs={:#x}", offset);
+    }
+
+    fn process_queue(&mut self, queue_index: usize) {
+        match queue_index {
+            RECEIVEQ => self.process_receiveq(),
+            TRANSMITQ => self.process_transmitq(),
+            _ => panic!("virtio-console: unknown queue {}", queue_index),
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use core::arch::asm;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::virtio::{VirtioDevice, VirtioMmioCommon};
+use crate::virtqueue::{ChainWriter, DescriptorChain, VirtqDesc, VIRTQ_DESC_F_WRITE};
+
+const VIRTIO_ENTROPY_IRQ: u16 = 2; // see VirtioDevice::irq
+const REQUESTQ: usize = 0;
+
+pub static VIRTIO_ENTROPY: Mutex<VirtioEntropy> = Mutex::new(VirtioEntropy::new());
+
+fn read_time() -> u64 {
+    let mut value: u64;
+    unsafe {
+        asm!("csrr {}, time", out(reg) value);
+    }
+    value
+}
+
+/// xorshift64 PRNG (13/7/17 shift triple) with an xorshift64*-style multiplied
+/// output, seeded from the `time` CSR. Good enough to unblock a guest's
+/// `/dev/hwrng`; not suitable for anything that needs cryptographic randomness.
+struct Xorshift64 {
+    state: u64,
+}
+
+impl Xorshift64 {
+    fn new(seed: u64) -> Self {
+        Self { state: if seed == 0 { 0xdead_beef } else { seed } }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state = x;
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+pub struct VirtioEntropy {
+    common: VirtioMmioCommon,
+}
+
+impl VirtioEntropy {
+    pub const fn new() -> Self {
+        Self { common: VirtioMmioCommon::new() }
+    }
+}
+
+impl VirtioDevice for VirtioEntropy {
+    const DEVICE_ID: u32 = 4;
+
+    fn irq(&self) -> u16 {
+        VIRTIO_ENTROPY_IRQ
+    }
+
+    fn common(&self) -> &VirtioMmioCommon {
+        &self.common
+    }
+
+    fn common_mut(&mut self) -> &mut VirtioMmioCommon {
+        &mut self.common
+    }
+
+    fn config_read(&self, offset: u64, _width: u64) -> u64 {
+        panic!("virtio-entropy has no device-specific config: offs={:#x}", offset);
+    }
+
+    fn process_queue(&mut self, queue_index: usize) {
+        assert_eq!(queue_index, REQUESTQ, "virtio-entropy has a single requestq");
+        let mut rng = Xorshift64::new(read_time());
+
+        while let Some(head) = self.common.pop_avail(REQUESTQ) {
+            let desc_table_addr = self.common.desc_table_addr(REQUESTQ);
+            let queue_size = self.common.queue_size(REQUESTQ);
+            let descs: Vec<(u16, VirtqDesc)> = DescriptorChain::new(desc_table_addr, queue_size, head).collect();
+            for (_, desc) in &descs {
+                assert!(desc.flags & VIRTQ_DESC_F_WRITE != 0, "virtio-entropy requestq buffers must be device-writable");
+            }
+            let total_len: u32 = descs.iter().map(|(_, d)| d.len).sum();
+
+            let mut buf = vec![0u8; total_len as usize];
+            for chunk in buf.chunks_mut(8) {
+                chunk.copy_from_slice(&rng.next_u64().to_le_bytes()[..chunk.len()]);
+            }
+
+            let mut writer = ChainWriter::new(descs.iter().map(|(_, d)| *d));
+            writer.write_bytes(&buf);
+
+            self.complete(REQUESTQ, head, writer.bytes_written);
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! Split-virtqueue plumbing shared across virtio-mmio devices.
+
+use crate::guest_memory::GUEST_MEMORY;
+
+pub const VIRTQ_DESC_F_NEXT: u16 = 0x1;
+pub const VIRTQ_DESC_F_WRITE: u16 = 0x2;
+
+pub const MAX_QUEUE_SIZE: usize = 128; // ring sizes below are sized to match
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct VirtqDesc {
+    pub addr: u64,
+    pub len: u32,
+    pub flags: u16,
+    pub next: u16,
+}
+
+#[repr(C)]
+pub struct VirtqAvail {
+    pub flags: u16,
+    pub idx: u16,
+    pub ring: [u16; MAX_QUEUE_SIZE],
+}
+
+#[repr(C)]
+pub struct VirtqUsed {
+    pub flags: u16,
+    pub idx: u16,
+    pub ring: [VirtqUsedElem; MAX_QUEUE_SIZE],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VirtqUsedElem {
+    pub id: u32,  // Descriptor index
+    pub len: u32, // Length of data written
+}
+
+/// Walks a descriptor chain starting at `head`, yielding `(index, descriptor)` pairs.
+pub struct DescriptorChain {
+    desc_table_addr: u64,
+    queue_size: u32,
+    next_index: Option<u16>,
+    remaining: u32,
+}
+
+impl DescriptorChain {
+    pub fn new(desc_table_addr: u64, queue_size: u32, head: u16) -> Self {
+        Self { desc_table_addr, queue_size, next_index: Some(head), remaining: queue_size }
+    }
+}
+
+impl Iterator for DescriptorChain {
+    type Item = (u16, VirtqDesc);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = self.next_index?;
+        if self.remaining == 0 {
+            return None; // `next` looped back on itself
+        }
+        self.remaining -= 1;
+
+        let raw: VirtqDesc = GUEST_MEMORY.read(self.desc_table_addr + index as u64 * size_of::<VirtqDesc>() as u64);
+        let desc = VirtqDesc {
+            addr: u64::from_le(raw.addr),
+            len: u32::from_le(raw.len),
+            flags: u16::from_le(raw.flags),
+            next: u16::from_le(raw.next),
+        };
+
+        self.next_index = if desc.flags & VIRTQ_DESC_F_NEXT != 0 { Some(desc.next) } else { None };
+        Some((index, desc))
+    }
+}
+
+/// Streams bytes out of a run of descriptors, advancing as each is exhausted.
+pub struct ChainReader<I: Iterator<Item = VirtqDesc>> {
+    descs: I,
+    cursor: Option<(u64, u32)>, // (remaining addr, remaining len) in the current descriptor
+}
+
+impl<I: Iterator<Item = VirtqDesc>> ChainReader<I> {
+    pub fn new(descs: I) -> Self {
+        Self { descs, cursor: None }
+    }
+
+    /// Fills `dst` from the chain. Panics if the chain runs out before `dst` is full.
+    pub fn read_bytes(&mut self, dst: &mut [u8]) {
+        let mut filled = 0;
+        while filled < dst.len() {
+            if !matches!(self.cursor, Some((_, len)) if len > 0) {
+                let desc = self.descs.next().expect("descriptor chain exhausted before buffer was filled");
+                self.cursor = Some((desc.addr, desc.len));
+            }
+            let (addr, len) = self.cursor.unwrap();
+            let n = core::cmp::min(len as usize, dst.len() - filled);
+            GUEST_MEMORY.read_bytes(addr, &mut dst[filled..filled + n]);
+            self.cursor = Some((addr + n as u64, len - n as u32));
+            filled += n;
+        }
+    }
+}
+
+/// Streams bytes into a run of descriptors, tracking total bytes written.
+pub struct ChainWriter<I: Iterator<Item = VirtqDesc>> {
+    descs: I,
+    cursor: Option<(u64, u32)>,
+    pub bytes_written: u32,
+}
+
+impl<I: Iterator<Item = VirtqDesc>> ChainWriter<I> {
+    pub fn new(descs: I) -> Self {
+        Self { descs, cursor: None, bytes_written: 0 }
+    }
+
+    /// Drains `src` into the chain. Panics if the chain runs out before `src` is drained.
+    pub fn write_bytes(&mut self, src: &[u8]) {
+        let mut written = 0;
+        while written < src.len() {
+            if !matches!(self.cursor, Some((_, len)) if len > 0) {
+                let desc = self.descs.next().expect("descriptor chain exhausted before buffer was drained");
+                self.cursor = Some((desc.addr, desc.len));
+            }
+            let (addr, len) = self.cursor.unwrap();
+            let n = core::cmp::min(len as usize, src.len() - written);
+            GUEST_MEMORY.write_bytes(addr, &src[written..written + n]);
+            self.cursor = Some((addr + n as u64, len - n as u32));
+            written += n;
+            self.bytes_written += n as u32;
+        }
+    }
+}